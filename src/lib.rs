@@ -1,9 +1,19 @@
 extern crate rand;
 extern crate regex;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+#[cfg(feature = "yaml")]
+extern crate serde_yaml;
 
 /// Module contains all methods and sturcts related to creating and using Markov
 /// chains to generate text.
 pub mod markov {
+    use std::collections::HashMap;
+    use std::fs::File;
+    use std::io;
+    use std::path::Path;
     use regex::Regex;
     use rand::random;
 
@@ -44,12 +54,46 @@ pub mod markov {
         Any,
     }
 
+    /// Configuration for `Markov::generate_best`, controlling how many candidate
+    /// sentences are tried and what makes a candidate acceptable.
+    pub struct GenerateConfig {
+        /// The minimum number of words a candidate sentence must contain to be accepted.
+        pub min_words: usize,
+        /// The maximum number of words a candidate sentence may contain.
+        pub max_words: usize,
+        /// How many candidate sentences to generate; the best accepted one is kept.
+        pub max_tries: usize,
+        /// The minimum score (see `Markov::generate_best`) a candidate must reach to be accepted.
+        pub min_score: f64,
+    }
+
+    /// A node in the graph produced by `Markov::to_graph`: a chain prefix and the
+    /// weighted transitions observed out of it.
+    pub struct GraphNode {
+        /// The prefix this node represents.
+        pub prefix: Vec<String>,
+        /// Each continuation observed out of `prefix`, paired with how many times
+        /// it was observed.
+        pub edges: Vec<(String, usize)>,
+    }
+
+    /// A directed graph of the chain states learned by a `Markov`, as produced by
+    /// `Markov::to_graph`.
+    pub struct MarkovGraph {
+        /// One node per distinct prefix observed during training.
+        pub nodes: Vec<GraphNode>,
+    }
+
     /// The structure used to represent a chain state.
     pub struct Markov {
-        /// Every way to begin a sentence that has been fed into the struct.
-        seeds: Vec<(String, String)>,
-        /// Holds every chain that has been fed into the struct.
-        chains: Vec<(String, String, Vec<String>)>,
+        /// Every way to begin a sentence that has been fed into the struct, stored as
+        /// `order`-length prefixes.
+        seeds: Vec<Vec<String>>,
+        /// Holds every chain that has been fed into the struct, keyed by the
+        /// `order`-length prefix that precedes each observed continuation.
+        chains: HashMap<Vec<String>, Vec<String>>,
+        /// The number of words used as a prefix when building and looking up chains.
+        order: usize,
         /// What should be done to input (see LetterCase enum)
         case: LetterCase,
         /// A vector of regexes that will be applied all incoming data in the order that
@@ -59,11 +103,22 @@ pub mod markov {
         transform: Option<Box<Fn(&str) -> String>>,
     }
 
-    /// Creates a new empty Markov object.
+    /// Creates a new empty Markov object using the default chain order of 2
+    /// (i.e. each word is predicted from the two words that precede it).
     pub fn new() -> Markov {
+        with_order(2)
+    }
+
+    /// Creates a new empty Markov object that uses `order` words of context to
+    /// predict the next word, rather than the default of 2. Higher orders need
+    /// more training data to fill out but tend to produce more coherent text.
+    /// # Arguments
+    /// * `order` - The number of words making up a chain prefix.
+    pub fn with_order(order: usize) -> Markov {
         Markov {
-            seeds : vec![],
-            chains: vec![],
+            seeds: vec![],
+            chains: HashMap::new(),
+            order: order,
             case: LetterCase::Any,
             transform: None,
             filters: vec![]
@@ -77,6 +132,17 @@ pub mod markov {
         sentence.split_whitespace().collect()
     }
 
+    /// A serializable snapshot of a trained `Markov`'s `order`, `seeds` and
+    /// `chains`. `case`, `filters`, and `transform` are not included since the
+    /// latter can't be serialized; `Markov::load` restores them to their
+    /// defaults.
+    #[derive(Serialize, Deserialize)]
+    struct MarkovData {
+        order: usize,
+        seeds: Vec<Vec<String>>,
+        chains: Vec<(Vec<String>, Vec<String>)>,
+    }
+
     impl Markov {
 
         /// Adds a regex to the list of filters that will be applied to all incoming data
@@ -84,6 +150,74 @@ pub mod markov {
             self.filters.push(reg);
         }
 
+        /// Saves the trained `order`, `seeds` and `chains` to `path` as JSON, so the
+        /// model can be reloaded with `load` instead of retraining. `case`, `filters`,
+        /// and `transform` are not saved.
+        /// # Arguments
+        /// * `path` - Where to write the serialized model.
+        pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+            let data = MarkovData {
+                order: self.order,
+                seeds: self.seeds.clone(),
+                chains: self.chains.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            };
+            let file = File::create(path)?;
+            serde_json::to_writer(file, &data).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        }
+
+        /// Loads a model previously written by `save`. `case` is reset to
+        /// `LetterCase::Any` and `filters`/`transform` are reset to empty/`None`,
+        /// since they could not be serialized.
+        /// # Arguments
+        /// * `path` - The path of a file written by `save`.
+        pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Markov> {
+            let file = File::open(path)?;
+            let data: MarkovData = serde_json::from_reader(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            Ok(Markov {
+                order: data.order,
+                seeds: data.seeds,
+                chains: data.chains.into_iter().collect(),
+                case: LetterCase::Any,
+                filters: vec![],
+                transform: None,
+            })
+        }
+
+        /// Saves the trained `order`, `seeds` and `chains` to `path` as YAML.
+        /// Only available with the `yaml` cargo feature enabled.
+        /// # Arguments
+        /// * `path` - Where to write the serialized model.
+        #[cfg(feature = "yaml")]
+        pub fn save_yaml<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+            let data = MarkovData {
+                order: self.order,
+                seeds: self.seeds.clone(),
+                chains: self.chains.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            };
+            let file = File::create(path)?;
+            serde_yaml::to_writer(file, &data).map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+        }
+
+        /// Loads a model previously written by `save_yaml`.
+        /// Only available with the `yaml` cargo feature enabled.
+        /// # Arguments
+        /// * `path` - The path of a file written by `save_yaml`.
+        #[cfg(feature = "yaml")]
+        pub fn load_yaml<P: AsRef<Path>>(path: P) -> io::Result<Markov> {
+            let file = File::open(path)?;
+            let data: MarkovData = serde_yaml::from_reader(file)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            Ok(Markov {
+                order: data.order,
+                seeds: data.seeds,
+                chains: data.chains.into_iter().collect(),
+                case: LetterCase::Any,
+                filters: vec![],
+                transform: None,
+            })
+        }
+
         /// Generates a string using the data passed into the markov chain.
         ///
         /// ## Returns
@@ -98,11 +232,13 @@ pub mod markov {
                 return None;
             }
 
-            let ref x = self.seeds[random::<usize>() % self.seeds.len()];
-            let mut words = vec![x.0.to_string(), x.1.to_string()];
-            for i in 0usize..(length-2) as usize {
-                let next_string = self.next(&words[i], &words[i+1usize]);
-                match next_string {
+            let ref seed = self.seeds[random::<usize>() % self.seeds.len()];
+            let mut words = seed.clone();
+            words.truncate(length as usize);
+            for _ in 0..(length as usize).saturating_sub(self.order) {
+                let prefix_start = words.len() - self.order;
+                let prefix = words[prefix_start..].to_vec();
+                match self.next(&prefix) {
                     Some(s) => {
                         words.push(s);
                     },
@@ -114,6 +250,162 @@ pub mod markov {
             return Some(words.join(" "));
         }
 
+        /// Generates `config.max_tries` candidate sentences and returns the
+        /// best-scoring one that satisfies `config`, along with its score.
+        ///
+        /// A candidate's score is the sum, over every transition taken during its
+        /// random walk, of how many continuations were observed in training for
+        /// the prefix it left from (i.e. the length of the matching chain entry
+        /// before the random selection), divided by the number of words in the
+        /// candidate, seed words included. Candidates
+        /// shorter than `config.min_words`, longer than `config.max_words`, scoring
+        /// below `config.min_score`, or rejected by `filter` are discarded.
+        ///
+        /// ## Returns
+        /// * `None` - If the markov chain is empty or no candidate satisfied `config`.
+        /// * `Some((sentence, score))` - The best accepted candidate and its score.
+        /// # Arguments
+        /// * `config` - Constraints on candidate length, score, and how many tries to make.
+        /// * `filter` - An optional callback that can reject a candidate sentence,
+        ///     e.g. to avoid returning verbatim training input or duplicate output.
+        pub fn generate_best(&self, config: GenerateConfig, mut filter: Option<&mut FnMut(&str) -> bool>) -> Option<(String, f64)> {
+            if self.seeds.len() == 0 {
+                return None;
+            }
+
+            let mut best: Option<(String, f64)> = None;
+
+            'tries: for _ in 0..config.max_tries {
+                let ref seed = self.seeds[random::<usize>() % self.seeds.len()];
+                let mut words = seed.clone();
+                let mut total_score = 0f64;
+
+                while words.len() < config.max_words {
+                    let prefix_start = words.len() - self.order;
+                    let prefix = words[prefix_start..].to_vec();
+                    match self.next_scored(&prefix) {
+                        Some((word, weight)) => {
+                            words.push(word);
+                            total_score += weight;
+                        },
+                        None => break,
+                    }
+                }
+
+                if words.len() < config.min_words || words.len() > config.max_words {
+                    continue 'tries;
+                }
+
+                let score = total_score / words.len() as f64;
+                if score < config.min_score {
+                    continue 'tries;
+                }
+
+                let sentence = words.join(" ");
+                if let Some(ref mut f) = filter {
+                    if !f(&sentence) {
+                        continue 'tries;
+                    }
+                }
+
+                let better = match best {
+                    Some((_, best_score)) => score > best_score,
+                    None => true,
+                };
+                if better {
+                    best = Some((sentence, score));
+                }
+            }
+
+            best
+        }
+
+        /// Generates a string starting from a caller-supplied prefix instead of a
+        /// random seed, so the caller controls how the sentence opens rather than
+        /// getting whatever `generate` would have picked.
+        /// ## Returns
+        /// * `None` - If `start` is not exactly `order` words, or no chain begins with it.
+        /// * `Some(sentence)` - The generated sentence, starting with `start`.
+        /// # Arguments
+        /// * `start` - The `order`-length prefix to begin the walk from.
+        /// * `length` - The maximum length of the sentence. The words of `start` are
+        ///     always all included, even when `length` is smaller than `order`.
+        pub fn generate_from(&self, start: &[&str], length: i32) -> Option<String> {
+            if start.len() != self.order {
+                return None;
+            }
+
+            let prefix: Vec<String> = start.iter().map(|s| s.to_string()).collect();
+            if !self.has_chain(&prefix) {
+                return None;
+            }
+
+            let mut words = prefix;
+            for _ in 0..(length as usize).saturating_sub(self.order) {
+                let prefix_start = words.len() - self.order;
+                let prefix = words[prefix_start..].to_vec();
+                match self.next(&prefix) {
+                    Some(s) => {
+                        words.push(s);
+                    },
+                    None => {
+                        return Some(words.join(" "));
+                    }
+                };
+            }
+            Some(words.join(" "))
+        }
+
+        /// Builds a directed graph of the learned chain: one node per prefix, with
+        /// edges to each observed continuation weighted by how many times it was
+        /// observed. Built directly from `chains`, so it reflects exactly what
+        /// training produced.
+        pub fn to_graph(&self) -> MarkovGraph {
+            let mut nodes: Vec<GraphNode> = vec![];
+            for (prefix, continuations) in self.chains.iter() {
+                let mut edges: Vec<(String, usize)> = vec![];
+                for word in continuations.iter() {
+                    let mut found = false;
+                    for edge in edges.iter_mut() {
+                        if edge.0 == *word {
+                            edge.1 += 1;
+                            found = true;
+                            break;
+                        }
+                    }
+                    if !found {
+                        edges.push((word.clone(), 1));
+                    }
+                }
+                nodes.push(GraphNode { prefix: prefix.clone(), edges: edges });
+            }
+            nodes.sort_by(|a, b| a.prefix.cmp(&b.prefix));
+            MarkovGraph { nodes: nodes }
+        }
+
+        /// Escapes `"` and `\` so a string can be safely embedded in a DOT
+        /// double-quoted identifier.
+        fn dot_escape(s: &str) -> String {
+            s.replace('\\', "\\\\").replace('"', "\\\"")
+        }
+
+        /// Emits the learned chain as Graphviz DOT source, so it can be rendered
+        /// with `dot`/`graphviz` to see what `to_graph` describes.
+        pub fn to_dot(&self) -> String {
+            let graph = self.to_graph();
+            let mut dot = String::new();
+            dot.push_str("digraph markov {\n");
+            for node in graph.nodes.iter() {
+                let from = Markov::dot_escape(&node.prefix.join(" "));
+                for &(ref word, count) in node.edges.iter() {
+                    let word = Markov::dot_escape(word);
+                    dot.push_str(&format!("    \"{}\" -> \"{}\" [label=\"{}\"];\n", from, word, count));
+                }
+            }
+            dot.push_str("}\n");
+            dot
+        }
+
         /// Adds a vector of sentences to the MarkovChain
         /// # Arguments
         /// * `sentences` - A vector of string slices to be added
@@ -123,7 +415,7 @@ pub mod markov {
         /// let data = vec!["Hello, how are you?", "What are you going to wear tonight?", "What time is it?"];
         /// markov.add_filter(markov::non_alphanumeric_regex());
         /// markov.pass(data);
-        /// assert!(markov.seeds.contains(&("Hello".to_string(), "how".to_string())));
+        /// assert!(markov.seeds.contains(&vec!["Hello".to_string(), "how".to_string()]));
         /// ```
         pub fn pass<'a>(&mut self, sentences: Vec<&'a str>) {
             for x in sentences {
@@ -166,51 +458,60 @@ pub mod markov {
                     words[i] = words[i].to_uppercase();
                 }
             }
-            if words.len() < 2 {
+            if words.len() < self.order {
                 false
             } else {
-                self.seeds.push((words[0].to_string(), words[1].to_string()));
-                for i in 0..words.len()-2 {
-                    self.add(&words[i], &words[i + 1], &words[i + 2]);
+                self.seeds.push(words[0..self.order].to_vec());
+                for i in 0..words.len().saturating_sub(self.order) {
+                    let prefix = words[i..i + self.order].to_vec();
+                    self.add(&prefix, &words[i + self.order]);
                 }
                 true
             }
         }
 
-        fn next(&self, s1: &String, s2: &String) -> Option<String> {
+        fn next(&self, prefix: &[String]) -> Option<String> {
             use rand::random;
 
-            for &(ref v1, ref v2, ref v3) in self.chains.iter() {
-                if *v1 == *s1 && *v2 == *s2 {
-                    let index = random::<usize>() % v3.len();
-                    return Some(v3[index].clone())
-                }
+            match self.chains.get(prefix) {
+                Some(v) => {
+                    let index = random::<usize>() % v.len();
+                    Some(v[index].clone())
+                },
+                None => None,
             }
-            None
         }
 
-        fn has_chain(&self, s1: &str, s2: &str) -> bool {
-            for &(ref v1, ref v2, _) in self.chains.iter() {
-                if v1 == s1 && v2 == s2 {
-                    return true
-                }
+        /// Like `next`, but also returns the total number of continuations
+        /// observed for `prefix` (the length of its chain entry), used by
+        /// `generate_best` to score candidates.
+        fn next_scored(&self, prefix: &[String]) -> Option<(String, f64)> {
+            use rand::random;
+
+            match self.chains.get(prefix) {
+                Some(v) => {
+                    let index = random::<usize>() % v.len();
+                    Some((v[index].clone(), v.len() as f64))
+                },
+                None => None,
             }
-            false
         }
 
-        fn add_to_chain(&mut self, s1: &str, s2: &str, word: &str) {
-            for &mut (ref v1, ref v2, ref mut v3) in self.chains.iter_mut() {
-                if v1 == s1 && v2 == s2 {
-                    v3.push(word.to_string());
-                }
+        fn has_chain(&self, prefix: &[String]) -> bool {
+            self.chains.contains_key(prefix)
+        }
+
+        fn add_to_chain(&mut self, prefix: &[String], word: &str) {
+            if let Some(v) = self.chains.get_mut(prefix) {
+                v.push(word.to_string());
             }
         }
 
-        fn add(&mut self, s1: &str, s2: &str, next: &str) {
-            if self.has_chain(s1, s2) {
-                self.add_to_chain(s1, s2, next);
+        fn add(&mut self, prefix: &[String], next: &str) {
+            if self.has_chain(prefix) {
+                self.add_to_chain(prefix, next);
             } else {
-                self.chains.push((s1.to_string(), s2.to_string(), vec![next.to_string()]));
+                self.chains.insert(prefix.to_vec(), vec![next.to_string()]);
             }
         }
     }
@@ -233,13 +534,105 @@ pub mod markov {
             assert!(m.generate(100).unwrap() == "hello how are you".to_string());
         }
 
+        #[test]
+        fn test_save_load() {
+            use std::env::temp_dir;
+
+            let mut m = markov::new();
+            m.pass_str("hello how are you");
+
+            let path = temp_dir().join("markovtextgen_test_save_load.json");
+            m.save(&path).unwrap();
+
+            let loaded = markov::Markov::load(&path).unwrap();
+            assert!(loaded.generate(100).unwrap() == "hello how are you".to_string());
+        }
+
+        #[test]
+        fn test_generate_best() {
+            let mut m = markov::new();
+            m.pass_str("hello how are you");
+
+            let config = markov::GenerateConfig {
+                min_words: 1,
+                max_words: 10,
+                max_tries: 20,
+                min_score: 0.0,
+            };
+            let (sentence, score) = m.generate_best(config, None).unwrap();
+            assert!(sentence == "hello how are you".to_string());
+            assert!(score >= 0.0);
+        }
+
+        #[test]
+        fn test_generate_best_rejects_over_max_words() {
+            let mut m = markov::new();
+            m.pass_str("hello how are you");
+
+            let config = markov::GenerateConfig {
+                min_words: 0,
+                max_words: 1,
+                max_tries: 20,
+                min_score: 0.0,
+            };
+            assert!(m.generate_best(config, None).is_none());
+        }
+
+        #[test]
+        fn test_generate_from() {
+            let mut m = markov::new();
+            m.pass_str("hello how are you");
+            assert!(m.generate_from(&["hello", "how"], 100).unwrap() == "hello how are you".to_string());
+            assert!(m.generate_from(&["not", "seen"], 100).is_none());
+            assert!(m.generate_from(&["hello"], 100).is_none());
+        }
+
+        #[test]
+        fn test_to_graph_and_dot() {
+            let mut m = markov::new();
+            m.pass_str("hello how are you");
+
+            let graph = m.to_graph();
+            assert!(graph.nodes.len() == 2);
+            for node in graph.nodes.iter() {
+                assert!(node.edges.len() == 1);
+            }
+
+            let dot = m.to_dot();
+            assert!(dot.starts_with("digraph markov {\n"));
+            assert!(dot.contains("\"hello how\" -> \"are\""));
+        }
+
+        #[test]
+        fn test_to_dot_escapes_quotes_and_backslashes() {
+            let mut m = markov::new();
+            m.pass_str("he said \"hi\" today");
+
+            let dot = m.to_dot();
+            assert!(dot.contains("\"he said\" -> \"\\\"hi\\\"\""));
+            assert!(dot.contains("\"said \\\"hi\\\"\" -> \"today\""));
+            assert!(!dot.contains("-> \"\"hi\"\""));
+
+            let mut m2 = markov::new();
+            m2.pass_str("back\\slash here now");
+            let dot2 = m2.to_dot();
+            assert!(dot2.contains("\"back\\\\slash here\" -> \"now\""));
+        }
+
+        #[test]
+        fn test_with_order() {
+            let mut m = markov::with_order(3);
+            m.pass_str("the quick brown fox jumps over the lazy dog");
+            assert!(m.generate(100).unwrap() == "the quick brown fox jumps over the lazy dog".to_string());
+        }
+
         #[test]
         fn test_pass() {
             let mut m = markov::new();
             let data = vec!["Hello, how are you?", "What are you going to wear tonight?", "What time is it?"];
             m.add_filter(markov::non_alphanumeric_regex());
             m.pass(data);
-            assert!(m.seeds.contains(&("Hello".to_string(), "how".to_string())));
+            assert!(m.seeds.contains(&vec!["Hello".to_string(), "how".to_string()]));
             m.generate(10).unwrap();
         }
 